@@ -1,25 +1,48 @@
+use std::time::{Duration, SystemTime};
+
 use jwt::VerifyingAlgorithm;
 use permissions::{CanDeleteApp, CanViewApps};
 
 use gdp::{
     named::name,
     proof::Proof,
-    prop::{or_l, or_r},
+    prop::{and, or_l, or_r, And},
 };
 
 use crate::{
-    jwt::{has_azure_role, has_okta_role, Admin, Azure, Jwt, Key, Okta},
+    capability::{
+        grants_access, is_not_stale, GrantsAccess, NotRevoked, ResourceName, RevocationStore,
+    },
+    hierarchy::admin_implies_can_view_apps,
+    jwt::{
+        has_audience, has_azure_role, has_okta_role, is_valid_at, matches_issuer, Admin, Azure,
+        Jwt, Key, Okta,
+    },
     permissions::{can_delete_app, can_view_apps},
 };
 
+/// Clock skew tolerated when checking a JWT's `exp`/`nbf`/`iat` claims
+const CLOCK_LEEWAY: Duration = Duration::from_secs(30);
+
+/// The `aud` claim a token must carry to be accepted by this relying party
+const EXPECTED_AUDIENCE: &str = "apps-api";
+
+/// Marker for this relying party, used as the `Aud` type parameter of `IntendedFor`
+pub struct ThisService;
+
 pub mod jwt {
-    use std::ops::Deref;
+    use std::{
+        collections::HashMap,
+        ops::Deref,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
 
     use gdp::{
         named::{Name, Named},
         proof::{axiom, Proof},
+        prop::And,
     };
-    use jwt::{VerifyWithKey, VerifyingAlgorithm};
+    use jwt::{FromBase64, VerifyWithKey, VerifyingAlgorithm};
     use serde_json::Value;
 
     pub trait Role {
@@ -34,10 +57,27 @@ pub mod jwt {
         }
     }
 
-    pub struct Key<A, I>(A, I);
+    pub struct Key<A, I> {
+        algorithm: A,
+        /// Only carries the issuer type `I` so it can be threaded through as a phantom witness
+        /// (e.g. into `Proof<IssuedBy<'name, I>>`); never read at runtime.
+        #[allow(dead_code)]
+        issuer: I,
+        expected_issuer_claim: Option<String>,
+    }
     impl<A, I> Key<A, I> {
         pub fn new(key: A, issuer: I) -> Self {
-            Key(key, issuer)
+            Key {
+                algorithm: key,
+                issuer,
+                expected_issuer_claim: None,
+            }
+        }
+
+        /// Configure the `iss` claim value this key's tokens must carry, for use with `matches_issuer`
+        pub fn with_issuer_claim(mut self, issuer_claim: impl Into<String>) -> Self {
+            self.expected_issuer_claim = Some(issuer_claim.into());
+            self
         }
     }
 
@@ -65,9 +105,75 @@ pub mod jwt {
             key: &Key<impl VerifyingAlgorithm, I>,
             token_str: Named<'name, &str>,
         ) -> Result<(JwtOf<'name>, Proof<IssuedBy<'name, I>>), jwt::Error> {
-            let token = token_str.verify_with_key(&key.0)?;
+            let token = token_str.verify_with_key(&key.algorithm)?;
             Ok((JwtOf(token_str.name(), Jwt { token }), axiom()))
         }
+
+        /// Validate given token against a rotating set of keys, picking the right one by its `kid` header
+        pub fn new_from_jwks<'name, I>(
+            jwks: &JwkSet<I>,
+            token_str: Named<'name, &str>,
+        ) -> Result<
+            (
+                JwtOf<'name>,
+                Proof<IssuedBy<'name, I>>,
+                Proof<VerifiedWith<'name, Kid>>,
+            ),
+            JwksError,
+        > {
+            let kid = parse_kid(&token_str).ok_or(JwksError::MissingKid)?;
+            let key = jwks.keys.get(&kid).ok_or(JwksError::UnknownKid)?;
+            let token = token_str
+                .verify_with_key(key)
+                .map_err(|_| JwksError::SignatureInvalid)?;
+            Ok((JwtOf(token_str.name(), Jwt { token }), axiom(), axiom()))
+        }
+
+        /// Exposes the token's claims to other modules, which can't reach the private `token` field
+        pub(crate) fn claims(&self) -> &Value {
+            self.token.claims()
+        }
+    }
+
+    /// Reads the `kid` field out of a token's header without verifying it
+    fn parse_kid(token_str: &str) -> Option<String> {
+        let header = token_str.split('.').next()?;
+        Value::from_base64(header)
+            .ok()?
+            .get("kid")?
+            .as_str()
+            .map(str::to_owned)
+    }
+
+    /// A keyed collection of verifying keys for a single issuer, selected by their `kid`
+    pub struct JwkSet<I> {
+        keys: HashMap<String, Box<dyn VerifyingAlgorithm>>,
+        /// Same phantom-witness role as `Key::issuer`; never read at runtime.
+        #[allow(dead_code)]
+        issuer: I,
+    }
+
+    impl<I> JwkSet<I> {
+        pub fn new(
+            issuer: I,
+            keys: impl IntoIterator<Item = (String, Box<dyn VerifyingAlgorithm>)>,
+        ) -> Self {
+            JwkSet {
+                keys: keys.into_iter().collect(),
+                issuer,
+            }
+        }
+    }
+
+    /// Marker for the proof that a token was verified against a `JwkSet`-selected key
+    pub struct Kid;
+    pub struct VerifiedWith<'name, K>(Name<'name>, K);
+
+    #[derive(PartialEq, Debug)]
+    pub enum JwksError {
+        MissingKid,
+        UnknownKid,
+        SignatureInvalid,
     }
 
     /// Check that the token gotten from azure has given role
@@ -93,38 +199,336 @@ pub mod jwt {
     ) -> Option<Proof<HasRole<'name, R>>> {
         jwt.token.claims().get(role.name())?.as_bool()?.then(axiom)
     }
+
+    /// Tied to both the jwt's `'name` and the clock's `'now`, so a proof obtained for one
+    /// evaluation instant can't be reused at another
+    pub struct NotExpired<'name, 'now>(Name<'name>, Name<'now>);
+    pub struct Active<'name, 'now>(Name<'name>, Name<'now>);
+
+    /// Check that `now` falls within the token's `exp`/`nbf`/`iat` window, allowing `leeway` of clock skew.
+    /// A claim that is absent is treated as unconstrained.
+    pub fn is_valid_at<'name, 'now>(
+        jwt: &JwtOf<'name>,
+        now: Named<'now, SystemTime>,
+        leeway: Duration,
+    ) -> Option<Proof<And<NotExpired<'name, 'now>, Active<'name, 'now>>>> {
+        let now = now.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        let leeway = leeway.as_secs() as i64;
+        let claims = jwt.token.claims();
+
+        let not_expired = claims
+            .get("exp")
+            .and_then(Value::as_i64)
+            .map_or(true, |exp| now <= exp + leeway);
+        let active = claims
+            .get("nbf")
+            .or_else(|| claims.get("iat"))
+            .and_then(Value::as_i64)
+            .map_or(true, |nbf| now + leeway >= nbf);
+
+        (not_expired && active).then(axiom)
+    }
+
+    pub struct IntendedFor<'name, Aud>(Name<'name>, Aud);
+
+    /// Check that the `aud` claim (string or array form) names `expected` among its audiences
+    pub fn has_audience<'name, 'exp, Aud>(
+        jwt: &JwtOf<'name>,
+        expected: Named<'exp, &str>,
+    ) -> Option<Proof<IntendedFor<'name, Aud>>> {
+        let aud = jwt.token.claims().get("aud")?;
+        let matches = match aud.as_str() {
+            Some(aud) => aud == *expected,
+            None => aud
+                .as_array()?
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|aud| aud == *expected),
+        };
+        matches.then(axiom)
+    }
+
+    pub struct MatchesIssuer<'name, I>(Name<'name>, I);
+
+    /// Check that the `iss` claim matches the issuer configured on `key`. A key with no
+    /// issuer claim configured (see `with_issuer_claim`) has nothing to bind against, so it
+    /// is treated as unconstrained.
+    pub fn matches_issuer<'name, I>(
+        jwt: &JwtOf<'name>,
+        key: &Key<impl VerifyingAlgorithm, I>,
+    ) -> Option<Proof<MatchesIssuer<'name, I>>> {
+        let Some(expected) = key.expected_issuer_claim.as_deref() else {
+            return Some(axiom());
+        };
+        let iss = jwt.token.claims().get("iss")?.as_str()?;
+        (iss == expected).then(axiom)
+    }
 }
 
 pub mod permissions {
     use gdp::{
         named::Name,
         proof::{axiom, Proof},
-        prop::Or,
+        prop::{And, Or},
     };
 
-    use crate::jwt::{Admin, Azure, HasRole, IssuedBy, Okta};
+    use crate::jwt::{
+        Active, Admin, Azure, HasRole, IntendedFor, IssuedBy, MatchesIssuer, NotExpired, Okta,
+    };
 
     pub struct CanViewApps<'name>(Name<'name>);
-    /// One can view apps if they have JWT issued by azure or okta
-    pub fn can_view_apps<'name>(
+    /// One can view apps if they have JWT issued by azure or okta, matching that issuer's
+    /// configured `iss` claim, currently valid, and scoped to this relying party
+    pub fn can_view_apps<'name, 'now, Aud>(
         _: Proof<Or<IssuedBy<'name, Azure>, IssuedBy<'name, Okta>>>,
+        _: Proof<Or<MatchesIssuer<'name, Azure>, MatchesIssuer<'name, Okta>>>,
+        _: Proof<And<NotExpired<'name, 'now>, Active<'name, 'now>>>,
+        _: Proof<IntendedFor<'name, Aud>>,
     ) -> Proof<CanViewApps<'name>> {
         axiom()
     }
 
     pub struct CanDeleteApp<'name>(Name<'name>);
-    /// One can delete apps if they have JWT with admin role
-    pub fn can_delete_app<'name>(_: Proof<HasRole<'name, Admin>>) -> Proof<CanDeleteApp<'name>> {
+    /// One can delete apps if they have JWT with admin role, matching that issuer's configured
+    /// `iss` claim, currently valid, and scoped to this relying party
+    pub fn can_delete_app<'name, 'now, Aud>(
+        _: Proof<HasRole<'name, Admin>>,
+        _: Proof<Or<MatchesIssuer<'name, Azure>, MatchesIssuer<'name, Okta>>>,
+        _: Proof<And<NotExpired<'name, 'now>, Active<'name, 'now>>>,
+        _: Proof<IntendedFor<'name, Aud>>,
+    ) -> Proof<CanDeleteApp<'name>> {
         axiom()
     }
 }
 
+/// Encodes that stronger capabilities subsume weaker ones, so a proof of the former can be
+/// turned into a proof of the latter with `Proof::<Impl<_, _>>::elim` instead of re-deriving it
+pub mod hierarchy {
+    use gdp::{
+        proof::{axiom, Proof},
+        prop::{And, Impl},
+    };
+
+    use crate::{
+        jwt::{Active, Admin, HasRole, IntendedFor, NotExpired},
+        permissions::{CanDeleteApp, CanViewApps},
+    };
+
+    /// Holding the admin role is enough to view apps without separately proving the
+    /// azure-or-okta issuer disjunction, but the token must still be currently valid and
+    /// scoped to this relying party
+    pub fn admin_implies_can_view_apps<'name, 'now, Aud>() -> Proof<
+        Impl<
+            And<HasRole<'name, Admin>, And<And<NotExpired<'name, 'now>, Active<'name, 'now>>, IntendedFor<'name, Aud>>>,
+            CanViewApps<'name>,
+        >,
+    > {
+        axiom()
+    }
+
+    /// Being able to delete apps implies being able to view them
+    pub fn can_delete_app_implies_can_view_apps<'name>(
+    ) -> Proof<Impl<CanDeleteApp<'name>, CanViewApps<'name>>> {
+        axiom()
+    }
+}
+
+/// A reusable, data-driven authorization layer: resources and permissions are runtime values,
+/// but performing a guarded action still requires a compile-time `GrantsAccess`/`NotRevoked` proof
+pub mod capability {
+    use std::{
+        collections::HashSet,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use gdp::{
+        named::{Name, Named},
+        proof::{axiom, Proof},
+    };
+    use serde_json::Value;
+
+    use crate::jwt::JwtOf;
+
+    pub struct ResourceName(pub String);
+
+    /// Marker tying the proof to the kind of resource `R` it was checked against
+    pub struct GrantsAccess<'name, R>(Name<'name>, R);
+
+    /// Check that the token carries a `grants` claim authorizing `permission` on `resource`
+    pub fn grants_access<'name, R>(
+        jwt: &JwtOf<'name>,
+        resource: &ResourceName,
+        permission: &str,
+    ) -> Option<Proof<GrantsAccess<'name, R>>> {
+        jwt.claims()
+            .get("grants")?
+            .as_array()?
+            .iter()
+            .filter_map(Value::as_object)
+            .any(|grant| {
+                grant.get("resource").and_then(Value::as_str) == Some(resource.0.as_str())
+                    && grant
+                        .get("permissions")
+                        .and_then(Value::as_array)
+                        .map_or(false, |permissions| {
+                            permissions
+                                .iter()
+                                .filter_map(Value::as_str)
+                                .any(|p| p == permission)
+                        })
+            })
+            .then(axiom)
+    }
+
+    pub struct NotRevoked<'name>(Name<'name>);
+
+    /// Tracks revoked tokens by their `jti` claim
+    #[derive(Default)]
+    pub struct RevocationStore(HashSet<String>);
+
+    impl RevocationStore {
+        pub fn new() -> Self {
+            RevocationStore::default()
+        }
+
+        pub fn revoke(&mut self, jti: impl Into<String>) {
+            self.0.insert(jti.into());
+        }
+
+        /// Check that the token's `jti` claim hasn't been revoked
+        pub fn is_not_revoked<'name>(&self, jwt: &JwtOf<'name>) -> Option<Proof<NotRevoked<'name>>> {
+            let jti = jwt.claims().get("jti")?.as_str()?;
+            (!self.0.contains(jti)).then(axiom)
+        }
+    }
+
+    /// How long a token may be used after issuance before the capability layer treats it as stale
+    pub struct Ttl(pub Duration);
+
+    pub struct NotStale<'name, 'now>(Name<'name>, Name<'now>);
+
+    /// Check that the token's `iat` claim is within `ttl` of `now`; a missing `iat` is treated as
+    /// unconstrained, matching the absent-claim convention used by `jwt::is_valid_at`
+    pub fn is_not_stale<'name, 'now>(
+        jwt: &JwtOf<'name>,
+        now: Named<'now, SystemTime>,
+        ttl: &Ttl,
+    ) -> Option<Proof<NotStale<'name, 'now>>> {
+        let now = now.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        let ttl = ttl.0.as_secs() as i64;
+
+        jwt.claims()
+            .get("iat")
+            .and_then(Value::as_i64)
+            .map_or(true, |iat| now <= iat + ttl)
+            .then(axiom)
+    }
+}
+
+/// Ordered-message-delivery and non-repudiation modeling for a fair exchange with a trusted
+/// third party (TTP). Each step's handler requires a `Delivered` proof for the step before it,
+/// so steps can only run in order within a given `'session`.
+pub mod protocol {
+    use gdp::{
+        named::Name,
+        proof::{axiom, Proof},
+        prop::{And, Not},
+    };
+
+    use crate::jwt::IssuedBy;
+
+    pub struct Step1;
+    pub struct Step2;
+    pub struct Step3;
+
+    /// Marks that step `S` of the exchange has been delivered within `'session`
+    pub struct Delivered<'session, S>(Name<'session>, S);
+
+    /// The first step has no predecessor to wait on; it is witnessed by a freshly named session
+    pub fn start<'session>(_session: Name<'session>) -> Proof<Delivered<'session, Step1>> {
+        axiom()
+    }
+
+    /// Step two can only run once step one has been delivered in the same session
+    pub fn deliver_step_two<'session>(
+        _: Proof<Delivered<'session, Step1>>,
+    ) -> Proof<Delivered<'session, Step2>> {
+        axiom()
+    }
+
+    /// Step three can only run once step two has been delivered in the same session
+    pub fn deliver_step_three<'session>(
+        _: Proof<Delivered<'session, Step2>>,
+    ) -> Proof<Delivered<'session, Step3>> {
+        axiom()
+    }
+
+    /// Witnesses that a party sent `Msg`, for use in the `cannot_deny` non-repudiation proof
+    pub struct Sent<Msg>(Msg);
+
+    pub struct EvidenceOfOrigin<'session, Msg>(Name<'session>, Msg);
+    /// Obtained once a party's signature over `msg` has been verified
+    pub fn evidence_of_origin<'session, Msg>(
+        signature_valid: bool,
+    ) -> Option<Proof<EvidenceOfOrigin<'session, Msg>>> {
+        signature_valid.then(axiom)
+    }
+
+    pub struct EvidenceOfReceipt<'session, Msg>(Name<'session>, Msg);
+    /// Obtained once the counterparty's acknowledgement of `msg` has been verified
+    pub fn evidence_of_receipt<'session, Msg>(
+        ack_valid: bool,
+    ) -> Option<Proof<EvidenceOfReceipt<'session, Msg>>> {
+        ack_valid.then(axiom)
+    }
+
+    /// Given evidence that a party originated `msg`, they cannot consistently claim both to
+    /// have sent and not sent it
+    pub fn cannot_deny<'session, Msg>(
+        _origin: &Proof<EvidenceOfOrigin<'session, Msg>>,
+    ) -> Proof<Not<And<Sent<Msg>, Not<Sent<Msg>>>>> {
+        let sent: Proof<Sent<Msg>> = axiom();
+        sent.non_contra()
+    }
+
+    /// Marker issuer for the trusted third party, used as `Key<_, Ttp>`'s issuer parameter
+    pub struct Ttp;
+    pub struct PublishedByTtp<'session>(Name<'session>);
+
+    /// The TTP's signature having verified is treated as its publication of the exchange outcome
+    pub fn published_by_ttp<'session, 'name>(
+        _: Proof<IssuedBy<'name, Ttp>>,
+    ) -> Proof<PublishedByTtp<'session>> {
+        axiom()
+    }
+
+    /// Both parties must present the TTP's publication, alongside their own evidence and proof
+    /// that step two was delivered, to finalize the exchange
+    pub fn finalize<'session, MsgA, MsgB>(
+        step_two: Proof<Delivered<'session, Step2>>,
+        _party_a_origin: Proof<EvidenceOfOrigin<'session, MsgA>>,
+        _party_b_receipt: Proof<EvidenceOfReceipt<'session, MsgB>>,
+        _ttp_published: Proof<PublishedByTtp<'session>>,
+    ) -> Proof<Delivered<'session, Step3>> {
+        deliver_step_three(step_two)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum Error {
     NoRole,
     JwtParseFailed,
+    Expired,
+    AccessDenied,
+    Revoked,
+    WrongAudience,
+    WrongIssuer,
+    Stale,
 }
 
+/// How long a token may be used after issuance before the `capability` layer rejects it
+const CAPABILITY_TTL: capability::Ttl = capability::Ttl(Duration::from_secs(3600));
+
 pub fn delete_app(_: Proof<CanDeleteApp>) -> String {
     "Nuke it to the ground".to_owned()
 }
@@ -139,12 +543,27 @@ pub fn try_to_list_apps(
     okta_key: &Key<impl VerifyingAlgorithm, Okta>,
 ) -> Result<Vec<String>, Error> {
     name(&*token_str, |token_str| {
-        let p = Jwt::new(&azure_key, token_str.clone())
-            .map(|(_, p)| or_l(p))
-            .or_else(|_| Jwt::new(&okta_key, token_str).map(|(_, p)| or_r(p)))
-            .map_err(|_| Error::JwtParseFailed)?;
-        let p = can_view_apps(p);
-        Ok(list_apps(p))
+        let (jwt, p, iss) = Jwt::new(&azure_key, token_str.clone())
+            .map_err(|_| Error::JwtParseFailed)
+            .and_then(|(jwt, p)| {
+                let iss = matches_issuer(&jwt, azure_key).ok_or(Error::WrongIssuer)?;
+                Ok((jwt, or_l(p), or_l(iss)))
+            })
+            .or_else(|_| {
+                Jwt::new(&okta_key, token_str)
+                    .map_err(|_| Error::JwtParseFailed)
+                    .and_then(|(jwt, p)| {
+                        let iss = matches_issuer(&jwt, okta_key).ok_or(Error::WrongIssuer)?;
+                        Ok((jwt, or_r(p), or_r(iss)))
+                    })
+            })?;
+        let audience = name(EXPECTED_AUDIENCE, |aud| has_audience::<ThisService>(&jwt, aud))
+            .ok_or(Error::WrongAudience)?;
+        name(SystemTime::now(), |now| {
+            let temporal = is_valid_at(&jwt, now, CLOCK_LEEWAY).ok_or(Error::Expired)?;
+            let p = can_view_apps(p, iss, temporal, audience);
+            Ok(list_apps(p))
+        })
     })
 }
 
@@ -154,33 +573,128 @@ pub fn try_to_delete_app(
     okta_key: &Key<impl VerifyingAlgorithm, Okta>,
 ) -> Result<String, Error> {
     name(&*token_str, |token_str| {
-        let (_, p) = Jwt::new(&azure_key, token_str.clone())
+        let (jwt, p, iss) = Jwt::new(&azure_key, token_str.clone())
             .map_err(|_| Error::JwtParseFailed)
-            .and_then(|(jwt, p)| {
-                has_azure_role(&jwt, Admin, p)
-                    .map(|p| (jwt, p))
-                    .ok_or_else(|| Error::NoRole)
+            .and_then(|(jwt, issued_by)| {
+                let p = has_azure_role(&jwt, Admin, issued_by).ok_or(Error::NoRole)?;
+                let iss = matches_issuer(&jwt, azure_key).ok_or(Error::WrongIssuer)?;
+                Ok((jwt, p, or_l(iss)))
             })
             .or_else(|_| {
                 Jwt::new(&okta_key, token_str)
                     .map_err(|_| Error::JwtParseFailed)
-                    .and_then(|(jwt, p)| {
-                        has_okta_role(&jwt, Admin, p)
-                            .map(|p| (jwt, p))
-                            .ok_or_else(|| Error::NoRole)
+                    .and_then(|(jwt, issued_by)| {
+                        let p = has_okta_role(&jwt, Admin, issued_by).ok_or(Error::NoRole)?;
+                        let iss = matches_issuer(&jwt, okta_key).ok_or(Error::WrongIssuer)?;
+                        Ok((jwt, p, or_r(iss)))
                     })
-            })
-            .map_err(|_| Error::JwtParseFailed)?;
-        let p = can_delete_app(p);
-        Ok(delete_app(p))
+            })?;
+        let audience = name(EXPECTED_AUDIENCE, |aud| has_audience::<ThisService>(&jwt, aud))
+            .ok_or(Error::WrongAudience)?;
+        name(SystemTime::now(), |now| {
+            let temporal = is_valid_at(&jwt, now, CLOCK_LEEWAY).ok_or(Error::Expired)?;
+            let p = can_delete_app(p, iss, temporal, audience);
+            Ok(delete_app(p))
+        })
+    })
+}
+
+/// Lists apps for an admin token without separately proving the azure-or-okta issuer
+/// disjunction: admin subsumes view access via the `hierarchy` lattice
+pub fn try_to_list_apps_as_admin(
+    token_str: &str,
+    azure_key: &Key<impl VerifyingAlgorithm, Azure>,
+) -> Result<Vec<String>, Error> {
+    name(&*token_str, |token_str| {
+        let (jwt, issued_by) =
+            Jwt::new(&azure_key, token_str).map_err(|_| Error::JwtParseFailed)?;
+        let has_admin_role = has_azure_role(&jwt, Admin, issued_by).ok_or(Error::NoRole)?;
+        let audience = name(EXPECTED_AUDIENCE, |aud| has_audience::<ThisService>(&jwt, aud))
+            .ok_or(Error::WrongAudience)?;
+        name(SystemTime::now(), |now| {
+            let temporal = is_valid_at(&jwt, now, CLOCK_LEEWAY).ok_or(Error::Expired)?;
+            let p = admin_implies_can_view_apps()
+                .elim(and(has_admin_role, and(temporal, audience)));
+            Ok(list_apps(p))
+        })
+    })
+}
+
+/// Marker for the "apps" resource kind, used with the generic `capability` layer
+pub struct Apps;
+
+/// The action guarded by the `capability` layer: callable only with a `GrantsAccess`/`NotRevoked` proof
+pub fn access_apps(_: Proof<And<GrantsAccess<Apps>, NotRevoked>>) -> Vec<String> {
+    vec!["app1".to_owned(), "app2".to_owned()]
+}
+
+/// Lists apps via the data-driven `capability` layer instead of the bespoke `CanViewApps` guard
+pub fn try_to_access_apps(
+    token_str: &str,
+    azure_key: &Key<impl VerifyingAlgorithm, Azure>,
+    resource: &ResourceName,
+    permission: &str,
+    revocations: &RevocationStore,
+) -> Result<Vec<String>, Error> {
+    name(&*token_str, |token_str| {
+        let (jwt, _) = Jwt::new(&azure_key, token_str).map_err(|_| Error::JwtParseFailed)?;
+        let audience = name(EXPECTED_AUDIENCE, |aud| has_audience::<ThisService>(&jwt, aud))
+            .ok_or(Error::WrongAudience)?;
+        let access = grants_access::<Apps>(&jwt, resource, permission).ok_or(Error::AccessDenied)?;
+        let not_revoked = revocations.is_not_revoked(&jwt).ok_or(Error::Revoked)?;
+        name(SystemTime::now(), |now| {
+            let temporal = is_valid_at(&jwt, now.clone(), CLOCK_LEEWAY).ok_or(Error::Expired)?;
+            let not_stale = is_not_stale(&jwt, now, &CAPABILITY_TTL).ok_or(Error::Stale)?;
+            let p = and(
+                and(access, not_revoked),
+                and(temporal, and(audience, not_stale)),
+            );
+            Ok(access_apps(p.elim_l()))
+        })
+    })
+}
+
+/// A message sent by party A during the exchange
+pub struct Message;
+
+/// Drives a three-step fair exchange to completion: party A's signed message and party B's
+/// acknowledgement can only be finalized once the trusted third party has published the outcome
+pub fn run_fair_exchange(
+    ttp_key: &Key<impl VerifyingAlgorithm, protocol::Ttp>,
+    ttp_token_str: &str,
+    party_a_signature_valid: bool,
+    party_b_ack_valid: bool,
+) -> Result<String, Error> {
+    name(&*ttp_token_str, |ttp_token_str| {
+        let (_, issued_by_ttp) =
+            Jwt::new(ttp_key, ttp_token_str).map_err(|_| Error::JwtParseFailed)?;
+
+        name((), |session| {
+            let step_one = protocol::start(session.name());
+            let step_two = protocol::deliver_step_two(step_one);
+
+            let origin = protocol::evidence_of_origin::<Message>(party_a_signature_valid)
+                .ok_or(Error::NoRole)?;
+            let receipt = protocol::evidence_of_receipt::<Message>(party_b_ack_valid)
+                .ok_or(Error::NoRole)?;
+            let _ = protocol::cannot_deny(&origin);
+            let published = protocol::published_by_ttp(issued_by_ttp);
+
+            let _step_three = protocol::finalize(step_two, origin, receipt, published);
+            Ok("exchange finalized".to_owned())
+        })
     })
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
     use crate::{
+        capability::{ResourceName, RevocationStore},
         jwt::{Azure, Key, Okta},
-        try_to_delete_app, try_to_list_apps, Error,
+        protocol, run_fair_exchange, try_to_access_apps, try_to_delete_app, try_to_list_apps,
+        try_to_list_apps_as_admin, Error,
     };
     use serde_json::{json, Map, Value};
 
@@ -227,7 +741,24 @@ mod tests {
             "alg": "none",
             "from": "azure"
         });
-        let claims = json!({ "roles": roles });
+        let claims = json!({ "roles": roles, "aud": "apps-api" });
+        format!(
+            "{}.{}.",
+            header.to_base64().unwrap(),
+            claims.to_base64().unwrap()
+        )
+    }
+
+    fn construct_azure_jwt_with_claims(roles: Vec<&str>, extra_claims: Value) -> String {
+        let header = json!({
+            "alg": "none",
+            "from": "azure"
+        });
+        let mut claims = json!({ "roles": roles, "aud": "apps-api" });
+        claims
+            .as_object_mut()
+            .unwrap()
+            .extend(extra_claims.as_object().unwrap().clone());
         format!(
             "{}.{}.",
             header.to_base64().unwrap(),
@@ -240,10 +771,62 @@ mod tests {
             "alg": "none",
             "from": "okta"
         });
-        let val = Value::Object(Map::from_iter(
+        let mut claims = Map::from_iter(
             roles.into_iter().map(|r| (r.to_owned(), Value::Bool(true))),
-        ));
-        let claims = json!(val);
+        );
+        claims.insert("aud".to_owned(), json!("apps-api"));
+        let claims = json!(claims);
+        format!(
+            "{}.{}.",
+            header.to_base64().unwrap(),
+            claims.to_base64().unwrap()
+        )
+    }
+
+    fn construct_azure_jwt_with_grants(grants: Value, jti: Option<&str>) -> String {
+        let header = json!({
+            "alg": "none",
+            "from": "azure"
+        });
+        let mut claims = json!({ "roles": Vec::<&str>::new(), "grants": grants, "aud": "apps-api" });
+        if let Some(jti) = jti {
+            claims["jti"] = json!(jti);
+        }
+        format!(
+            "{}.{}.",
+            header.to_base64().unwrap(),
+            claims.to_base64().unwrap()
+        )
+    }
+
+    fn construct_azure_jwt_with_grants_and_iat(grants: Value, jti: &str, iat: i64) -> String {
+        let header = json!({
+            "alg": "none",
+            "from": "azure"
+        });
+        let claims = json!({
+            "roles": Vec::<&str>::new(),
+            "grants": grants,
+            "aud": "apps-api",
+            "jti": jti,
+            "iat": iat,
+        });
+        format!(
+            "{}.{}.",
+            header.to_base64().unwrap(),
+            claims.to_base64().unwrap()
+        )
+    }
+
+    fn construct_jwt_with_kid(fake: &str, kid: Option<&str>) -> String {
+        let mut header = json!({
+            "alg": "none",
+            "from": fake
+        });
+        if let Some(kid) = kid {
+            header["kid"] = json!(kid);
+        }
+        let claims = json!({ "roles": Vec::<&str>::new() });
         format!(
             "{}.{}.",
             header.to_base64().unwrap(),
@@ -307,4 +890,250 @@ mod tests {
             try_to_delete_app(&token_str, &azure_key, &okta_key)
         );
     }
+
+    #[test]
+    fn test_jwks_verifies_with_matching_kid() {
+        use crate::jwt::{Jwt, JwkSet};
+        use gdp::named::name;
+
+        let token_str = construct_jwt_with_kid("rotating", Some("key-2"));
+        let jwks = JwkSet::new(
+            Azure,
+            vec![
+                (
+                    "key-1".to_owned(),
+                    Box::new(DummyAlgo("other".to_owned())) as Box<dyn jwt::VerifyingAlgorithm>,
+                ),
+                (
+                    "key-2".to_owned(),
+                    Box::new(DummyAlgo("rotating".to_owned())) as Box<dyn jwt::VerifyingAlgorithm>,
+                ),
+            ],
+        );
+
+        name(&*token_str, |token_str| {
+            assert!(Jwt::new_from_jwks(&jwks, token_str).is_ok());
+        });
+    }
+
+    #[test]
+    fn test_jwks_rejects_missing_kid() {
+        use crate::jwt::{Jwt, JwkSet, JwksError};
+        use gdp::named::name;
+
+        let token_str = construct_jwt_with_kid("rotating", None);
+        let jwks: JwkSet<Azure> = JwkSet::new(Azure, vec![]);
+
+        name(&*token_str, |token_str| {
+            assert!(matches!(
+                Jwt::new_from_jwks(&jwks, token_str),
+                Err(JwksError::MissingKid)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_jwks_rejects_unknown_kid() {
+        use crate::jwt::{Jwt, JwkSet, JwksError};
+        use gdp::named::name;
+
+        let token_str = construct_jwt_with_kid("rotating", Some("key-404"));
+        let jwks: JwkSet<Azure> = JwkSet::new(Azure, vec![]);
+
+        name(&*token_str, |token_str| {
+            assert!(matches!(
+                Jwt::new_from_jwks(&jwks, token_str),
+                Err(JwksError::UnknownKid)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_jwks_rejects_bad_signature() {
+        use crate::jwt::{Jwt, JwkSet, JwksError};
+        use gdp::named::name;
+
+        let token_str = construct_jwt_with_kid("rotating", Some("key-1"));
+        let jwks = JwkSet::new(
+            Azure,
+            vec![(
+                "key-1".to_owned(),
+                Box::new(DummyAlgo("someone-else".to_owned())) as Box<dyn jwt::VerifyingAlgorithm>,
+            )],
+        );
+
+        name(&*token_str, |token_str| {
+            assert!(matches!(
+                Jwt::new_from_jwks(&jwks, token_str),
+                Err(JwksError::SignatureInvalid)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_app_listing_with_expired_token() {
+        let token_str =
+            construct_azure_jwt_with_claims(vec![], json!({ "exp": 0 }));
+        let azure_key = Key::new(DummyAlgo("azure".to_owned()), Azure);
+        let okta_key = Key::new(DummyAlgo("okta".to_owned()), Okta);
+
+        assert_eq!(
+            Err(Error::Expired),
+            try_to_list_apps(&token_str, &azure_key, &okta_key)
+        );
+    }
+
+    #[test]
+    fn test_app_listing_as_admin_via_hierarchy() {
+        let token_str = construct_azure_jwt(vec!["admin"]);
+        let azure_key = Key::new(DummyAlgo("azure".to_owned()), Azure);
+
+        assert_eq!(
+            Ok::<_, crate::Error>(vec!["app1".to_owned(), "app2".to_owned()]),
+            try_to_list_apps_as_admin(&token_str, &azure_key)
+        );
+    }
+
+    #[test]
+    fn test_app_listing_with_not_yet_valid_token() {
+        let far_future: i64 = 4_102_444_800; // 2100-01-01
+        let token_str =
+            construct_azure_jwt_with_claims(vec![], json!({ "nbf": far_future }));
+        let azure_key = Key::new(DummyAlgo("azure".to_owned()), Azure);
+        let okta_key = Key::new(DummyAlgo("okta".to_owned()), Okta);
+
+        assert_eq!(
+            Err(Error::Expired),
+            try_to_list_apps(&token_str, &azure_key, &okta_key)
+        );
+    }
+
+    #[test]
+    fn test_app_listing_with_wrong_audience_is_rejected() {
+        let token_str = construct_azure_jwt_with_claims(vec![], json!({ "aud": "other-api" }));
+        let azure_key = Key::new(DummyAlgo("azure".to_owned()), Azure);
+        let okta_key = Key::new(DummyAlgo("okta".to_owned()), Okta);
+
+        assert_eq!(
+            Err(Error::WrongAudience),
+            try_to_list_apps(&token_str, &azure_key, &okta_key)
+        );
+    }
+
+    #[test]
+    fn test_matches_issuer_succeeds_when_iss_matches_configured_value() {
+        use crate::jwt::{matches_issuer, Jwt};
+        use gdp::named::name;
+
+        let token_str = construct_azure_jwt_with_claims(
+            vec![],
+            json!({ "iss": "https://issuer.example" }),
+        );
+        let azure_key = Key::new(DummyAlgo("azure".to_owned()), Azure)
+            .with_issuer_claim("https://issuer.example");
+
+        name(&*token_str, |token_str| {
+            let (jwt, _) = Jwt::new(&azure_key, token_str).unwrap();
+            assert!(matches_issuer(&jwt, &azure_key).is_some());
+        });
+    }
+
+    #[test]
+    fn test_matches_issuer_fails_when_iss_does_not_match() {
+        use crate::jwt::{matches_issuer, Jwt};
+        use gdp::named::name;
+
+        let token_str = construct_azure_jwt_with_claims(
+            vec![],
+            json!({ "iss": "https://someone-else.example" }),
+        );
+        let azure_key = Key::new(DummyAlgo("azure".to_owned()), Azure)
+            .with_issuer_claim("https://issuer.example");
+
+        name(&*token_str, |token_str| {
+            let (jwt, _) = Jwt::new(&azure_key, token_str).unwrap();
+            assert!(matches_issuer(&jwt, &azure_key).is_none());
+        });
+    }
+
+    #[test]
+    fn test_capability_grants_access() {
+        let grants = json!([{ "resource": "apps", "permissions": ["read"] }]);
+        let token_str = construct_azure_jwt_with_grants(grants, Some("token-1"));
+        let azure_key = Key::new(DummyAlgo("azure".to_owned()), Azure);
+        let resource = ResourceName("apps".to_owned());
+        let revocations = RevocationStore::new();
+
+        assert_eq!(
+            Ok::<_, Error>(vec!["app1".to_owned(), "app2".to_owned()]),
+            try_to_access_apps(&token_str, &azure_key, &resource, "read", &revocations)
+        );
+    }
+
+    #[test]
+    fn test_capability_denies_missing_grant() {
+        let grants = json!([{ "resource": "apps", "permissions": ["write"] }]);
+        let token_str = construct_azure_jwt_with_grants(grants, Some("token-2"));
+        let azure_key = Key::new(DummyAlgo("azure".to_owned()), Azure);
+        let resource = ResourceName("apps".to_owned());
+        let revocations = RevocationStore::new();
+
+        assert_eq!(
+            Err(Error::AccessDenied),
+            try_to_access_apps(&token_str, &azure_key, &resource, "read", &revocations)
+        );
+    }
+
+    #[test]
+    fn test_capability_denies_revoked_token() {
+        let grants = json!([{ "resource": "apps", "permissions": ["read"] }]);
+        let token_str = construct_azure_jwt_with_grants(grants, Some("token-3"));
+        let azure_key = Key::new(DummyAlgo("azure".to_owned()), Azure);
+        let resource = ResourceName("apps".to_owned());
+        let mut revocations = RevocationStore::new();
+        revocations.revoke("token-3");
+
+        assert_eq!(
+            Err(Error::Revoked),
+            try_to_access_apps(&token_str, &azure_key, &resource, "read", &revocations)
+        );
+    }
+
+    #[test]
+    fn test_capability_denies_stale_token() {
+        let grants = json!([{ "resource": "apps", "permissions": ["read"] }]);
+        let issued = SystemTime::now() - Duration::from_secs(2 * 3600);
+        let iat = issued.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let token_str = construct_azure_jwt_with_grants_and_iat(grants, "token-4", iat);
+        let azure_key = Key::new(DummyAlgo("azure".to_owned()), Azure);
+        let resource = ResourceName("apps".to_owned());
+        let revocations = RevocationStore::new();
+
+        assert_eq!(
+            Err(Error::Stale),
+            try_to_access_apps(&token_str, &azure_key, &resource, "read", &revocations)
+        );
+    }
+
+    #[test]
+    fn test_fair_exchange_finalizes_with_ttp_publication() {
+        let ttp_token = construct_jwt("ttp", vec![]);
+        let ttp_key = Key::new(DummyAlgo("ttp".to_owned()), protocol::Ttp);
+
+        assert_eq!(
+            Ok("exchange finalized".to_owned()),
+            run_fair_exchange(&ttp_key, &ttp_token, true, true)
+        );
+    }
+
+    #[test]
+    fn test_fair_exchange_rejects_unpublished_ttp_token() {
+        let ttp_token = construct_jwt("someone-else", vec![]);
+        let ttp_key = Key::new(DummyAlgo("ttp".to_owned()), protocol::Ttp);
+
+        assert_eq!(
+            Err(Error::JwtParseFailed),
+            run_fair_exchange(&ttp_key, &ttp_token, true, true)
+        );
+    }
 }